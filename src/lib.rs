@@ -7,10 +7,14 @@
 extern crate alloc;
 use alloc::alloc::{GlobalAlloc, Layout};
 
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
 use core::{
-    cell::Cell,
+    cell::{Cell, RefCell},
     future::Future,
     pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
     task::{Context, Poll},
 };
 
@@ -18,12 +22,438 @@ use core::{
 pub use alloc_counter_macro::{count_alloc, no_alloc};
 
 // FIXME: static atomics for single-threaded no_std?
-thread_local!(static COUNTERS: Cell<Counters> = Cell::default());
-thread_local!(static MODE: Cell<AllocMode> = Cell::new(AllocMode::Count));
+thread_local!(static INFO: Cell<AllocInfo> = Cell::default());
+
+// Depth of nested guards (closure-shaped or RAII scopes) supported per thread. `MODE` is
+// backed by a fixed-size inline array rather than a `Vec`: `AllocCounter::alloc` reads it on
+// *every* allocation via `current_mode()`, including the very first one on a thread, which
+// would otherwise re-enter `alloc` while lazily allocating the backing storage for the
+// thread-local itself and overflow the stack.
+const MODE_STACK_CAPACITY: usize = 32;
+
+struct ModeStack {
+    modes: [AllocMode; MODE_STACK_CAPACITY],
+    len: usize,
+}
+
+impl Default for ModeStack {
+    fn default() -> Self {
+        ModeStack {
+            modes: [AllocMode::Count; MODE_STACK_CAPACITY],
+            len: 1,
+        }
+    }
+}
+
+// A stack rather than a single cell so nested guards compose: entering a guard pushes a
+// mode, dropping it pops back to whatever was active before, without needing to remember
+// and restore a captured "previous" value by hand.
+thread_local!(static MODE: RefCell<ModeStack> = RefCell::new(ModeStack::default()));
+
+/// Returns the `AllocMode` currently in effect on this thread.
+fn current_mode() -> AllocMode {
+    MODE.with(|stack| {
+        let stack = stack.borrow();
+        stack.modes[stack.len - 1]
+    })
+}
+
+/// Pushes `mode` onto this thread's mode stack, preserving `CountAll` precedence: if
+/// `CountAll` is already in effect, it stays in effect regardless of `mode`. Returns whether
+/// a new entry was actually pushed; past `MODE_STACK_CAPACITY` nesting depth, the stack
+/// doesn't grow (the innermost mode is overwritten in place) and this returns `false`, which
+/// the caller must pass back to [`pop_mode`] so the corresponding pop is skipped too — a
+/// push that didn't grow the stack must not be undone by a pop that shrinks it, or every
+/// live guard further out would have its mode restored one level too early.
+fn push_mode(mode: AllocMode) -> bool {
+    MODE.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        let effective = if stack.modes[stack.len - 1] == AllocMode::CountAll {
+            AllocMode::CountAll
+        } else {
+            mode
+        };
+        let len = stack.len;
+        if len < MODE_STACK_CAPACITY {
+            stack.modes[len] = effective;
+            stack.len += 1;
+            true
+        } else {
+            stack.modes[len - 1] = effective;
+            false
+        }
+    })
+}
+
+/// Pops a mode pushed with [`push_mode`]. `pushed` must be the value that call returned, so
+/// an over-capacity push (which didn't grow the stack) is correctly skipped here too.
+fn pop_mode(pushed: bool) {
+    if !pushed {
+        return;
+    }
+    MODE.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        if stack.len > 1 {
+            stack.len -= 1;
+        }
+    });
+}
+
+// Tracks, per active `count_alloc_info` call on this thread, the peak `bytes_current` seen
+// since that call started (nested calls each get their own entry, updated independently, so
+// an outer call's peak also reflects everything an inner call did). Bounded and inline for
+// the same reentrancy reason as `ModeStack`.
+const PEAK_STACK_CAPACITY: usize = 32;
+
+struct PeakStack {
+    peaks: [usize; PEAK_STACK_CAPACITY],
+    len: usize,
+}
+
+impl Default for PeakStack {
+    fn default() -> Self {
+        PeakStack {
+            peaks: [0; PEAK_STACK_CAPACITY],
+            len: 0,
+        }
+    }
+}
+
+thread_local!(static PEAKS: RefCell<PeakStack> = RefCell::new(PeakStack::default()));
+
+/// Starts tracking a new scoped peak, seeded with the live bytes already outstanding when
+/// the scope begins. Returns the index to pass to `pop_peak`, or `None` if nesting went past
+/// `PEAK_STACK_CAPACITY` (in which case the peak reported for that scope is just the larger
+/// of its entry and exit live bytes, a documented, generous limit rather than something real
+/// call stacks hit).
+fn push_peak(baseline: usize) -> Option<usize> {
+    PEAKS.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        let len = stack.len;
+        if len == PEAK_STACK_CAPACITY {
+            return None;
+        }
+        stack.peaks[len] = baseline;
+        stack.len += 1;
+        Some(len)
+    })
+}
+
+/// Ends the scope started by `push_peak`, returning the peak `bytes_current` observed while
+/// it was active.
+fn pop_peak(index: Option<usize>, fallback: usize) -> usize {
+    PEAKS.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        match index {
+            Some(index) if index + 1 == stack.len => {
+                stack.len -= 1;
+                stack.peaks[index]
+            }
+            _ => fallback,
+        }
+    })
+}
+
+/// Records a new `bytes_current` value against every scope currently tracked by `push_peak`.
+fn record_peak(current: usize) {
+    PEAKS.with(|stack| {
+        let mut stack = stack.borrow_mut();
+        let len = stack.len;
+        for peak in &mut stack.peaks[..len] {
+            if current > *peak {
+                *peak = current;
+            }
+        }
+    });
+}
+
+thread_local!(static CALLBACK: Cell<Option<AllocCallback>> = Cell::new(None));
+// Set while a registered callback is executing, so its own allocations are neither counted
+// nor re-dispatched into the callback, mirroring the `AllocMode::Ignore` short-circuit in the
+// `GlobalAlloc` impl below.
+thread_local!(static IN_CALLBACK: Cell<bool> = Cell::new(false));
+
+/// The kind of allocator operation passed to an [`AllocCallback`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AllocOp {
+    /// A call to `GlobalAlloc::alloc`.
+    Alloc,
+    /// A call to `GlobalAlloc::realloc`.
+    Realloc,
+    /// A call to `GlobalAlloc::dealloc`.
+    Dealloc,
+}
+
+/// Signature of a callback registered with [`set_alloc_callback`]: the operation kind, the
+/// `Layout` involved, and the resulting pointer (the freed pointer, for `AllocOp::Dealloc`).
+pub type AllocCallback = fn(AllocOp, Layout, *mut u8);
+
+/// Register a callback invoked on every `alloc`/`realloc`/`dealloc` performed by an
+/// `AllocCounter` on the current thread. The callback runs inside an internal reentrancy
+/// guard, so it may allocate (e.g. to log or capture a backtrace) without recursing into
+/// itself or polluting the counters. Replaces any previously registered callback.
+pub fn set_alloc_callback(callback: AllocCallback) {
+    CALLBACK.with(|c| c.set(Some(callback)));
+}
+
+/// Remove any callback previously installed with [`set_alloc_callback`].
+pub fn clear_alloc_callback() {
+    CALLBACK.with(|c| c.set(None));
+}
+
+// Restores `IN_CALLBACK` to `false` on drop, so a callback that panics (it's free to
+// allocate, and allocating code can panic) doesn't leave counting and callback dispatch
+// disabled for the rest of the thread's life.
+struct InCallbackGuard;
+
+impl Drop for InCallbackGuard {
+    fn drop(&mut self) {
+        IN_CALLBACK.with(|c| c.set(false));
+    }
+}
+
+fn dispatch_callback(op: AllocOp, layout: Layout, ptr: *mut u8) {
+    if let Some(callback) = CALLBACK.with(Cell::get) {
+        IN_CALLBACK.with(|c| c.set(true));
+        let _guard = InCallbackGuard;
+        callback(op, layout, ptr);
+    }
+}
 
 /// A tuple of the counts; respectively allocations, reallocations, and deallocations.
 pub type Counters = (usize, usize, usize);
 
+/// A richer allocation report, including byte-level totals and a running peak.
+///
+/// Unlike the plain `allocations`/`reallocations`/`deallocations` counts, `bytes_current`
+/// and `bytes_max` reflect live state rather than a delta between two points in time:
+/// `bytes_current` is the number of bytes currently live (allocated but not yet
+/// deallocated), and `bytes_max` is a high-water-mark of `bytes_current` that only ever
+/// rises for as long as it's being tracked. This lets callers assert that a function never
+/// exceeds a memory budget, not just a call count. See [`count_alloc_info`] for how
+/// `bytes_max` is scoped when returned from a specific call.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct AllocInfo {
+    /// Number of allocations.
+    pub allocations: usize,
+    /// Number of reallocations.
+    pub reallocations: usize,
+    /// Number of deallocations.
+    pub deallocations: usize,
+    /// Total bytes ever requested through `alloc`/`realloc`.
+    pub bytes_allocated: usize,
+    /// Bytes currently live (allocated but not yet deallocated).
+    pub bytes_current: usize,
+    /// High-water-mark of `bytes_current`; only ever increases while tracked.
+    pub bytes_max: usize,
+}
+
+// The handle targeted by `count_alloc_with`/`guard_fn_with` on this thread, if any; a stack
+// for the same reason `MODE` is one: nested `_with` scopes need to restore the enclosing
+// handle (or the thread-local fast path) on exit. The pointee outlives every entry, since
+// pushing one borrows from the `Arc<SharedCounters>` held by the caller for the
+// duration of the call.
+thread_local!(static ACTIVE_HANDLE: RefCell<Vec<*const SharedCounters>> = RefCell::new(Vec::new()));
+
+fn active_handle() -> Option<*const SharedCounters> {
+    // `try_borrow`: growing the stack in `with_active_handle` allocates while it's mutably
+    // borrowed, and that allocation re-enters here; it isn't routed to any handle.
+    // `try_with`: the stack has a destructor, and allocations still happen on this thread
+    // after it has run (other thread-locals being torn down); those aren't routed either.
+    ACTIVE_HANDLE
+        .try_with(|stack| stack.try_borrow().ok().and_then(|s| s.last().copied()))
+        .ok()
+        .flatten()
+}
+
+/// An atomic, `Arc`-shareable block of allocation counters.
+///
+/// The default thread-local counters used by [`count_alloc`]/[`guard_fn`] can't be
+/// aggregated across threads, so two independently wrapped allocators, or a workload spread
+/// over a thread pool or async executor, can't be measured as a whole. A `SharedCounters`
+/// handle fixes that: construct one with [`SharedCounters::new`], hand clones of the `Arc`
+/// to however many threads need to contribute to it, and have each thread route its
+/// accounting through the handle with [`count_alloc_with`]/[`guard_fn_with`]. Read back an
+/// aggregated snapshot at any time with [`SharedCounters::snapshot`].
+///
+/// Routing is opt-in and per-thread: which handle (if any) a thread's allocations count
+/// against is thread-local state, so it is *not* inherited by threads spawned from inside
+/// `count_alloc_with`/`guard_fn_with` — each worker thread must itself call
+/// `count_alloc_with(&handle, ...)` (typically wrapping its whole body) with a clone of the
+/// same `Arc` for its allocations to be aggregated into `handle`.
+#[derive(Default)]
+pub struct SharedCounters {
+    allocations: AtomicUsize,
+    reallocations: AtomicUsize,
+    deallocations: AtomicUsize,
+    bytes_allocated: AtomicUsize,
+    bytes_current: AtomicUsize,
+    bytes_max: AtomicUsize,
+}
+
+impl SharedCounters {
+    /// Create a new, zeroed counter block wrapped in an `Arc` so it can be shared with
+    /// several threads.
+    pub fn new() -> Arc<SharedCounters> {
+        Arc::new(SharedCounters::default())
+    }
+
+    /// Read a point-in-time snapshot of the counters as an [`AllocInfo`].
+    pub fn snapshot(&self) -> AllocInfo {
+        AllocInfo {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            reallocations: self.reallocations.load(Ordering::Relaxed),
+            deallocations: self.deallocations.load(Ordering::Relaxed),
+            bytes_allocated: self.bytes_allocated.load(Ordering::Relaxed),
+            bytes_current: self.bytes_current.load(Ordering::Relaxed),
+            bytes_max: self.bytes_max.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record_alloc(&self, size: usize) {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.bytes_allocated.fetch_add(size, Ordering::Relaxed);
+        let current = self.bytes_current.fetch_add(size, Ordering::Relaxed) + size;
+        self.bytes_max.fetch_max(current, Ordering::Relaxed);
+    }
+
+    fn record_realloc(&self, old_size: usize, new_size: usize) {
+        self.reallocations.fetch_add(1, Ordering::Relaxed);
+        self.bytes_allocated.fetch_add(new_size, Ordering::Relaxed);
+        let current = if new_size >= old_size {
+            self.bytes_current.fetch_add(new_size - old_size, Ordering::Relaxed) + (new_size - old_size)
+        } else {
+            // Saturating, via `fetch_update`, for the same reason as the thread-local path:
+            // a counted realloc of memory allocated while uncounted must not underflow.
+            let shrink = old_size - new_size;
+            self.bytes_current
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |cur| {
+                    Some(cur.saturating_sub(shrink))
+                })
+                .unwrap()
+                .saturating_sub(shrink)
+        };
+        self.bytes_max.fetch_max(current, Ordering::Relaxed);
+    }
+
+    fn record_dealloc(&self, size: usize) {
+        self.deallocations.fetch_add(1, Ordering::Relaxed);
+        // Saturating for the same reason as the thread-local path: a counted dealloc of
+        // memory allocated while uncounted must not underflow `bytes_current`.
+        let _ = self
+            .bytes_current
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |cur| Some(cur.saturating_sub(size)));
+    }
+}
+
+/// Like [`count_alloc`]/[`count_alloc_info`], but route the accounting for this thread, for
+/// the duration of `f`, through `handle` in addition to the thread-local counters. Several
+/// threads targeting the same handle accumulate into one aggregated total, making this
+/// suitable for measuring a thread-pool or async-executor workload as a whole rather than
+/// one thread's closure — but only for the threads that actually call this: routing is
+/// thread-local, so a thread spawned from inside `f` does *not* automatically count against
+/// `handle` unless it calls `count_alloc_with(&handle, ...)` itself with a clone of the
+/// `Arc`.
+///
+/// As with [`count_alloc_info`], `allocations`/`reallocations`/`deallocations`/
+/// `bytes_allocated` are deltas covering just this call, but `bytes_current` is *not*: it's
+/// `handle`'s absolute live-byte count, across every thread targeting it, at the moment `f`
+/// returns. Nor is `bytes_max` scoped to the call: it's `handle`'s high-water mark of
+/// `bytes_current` over the handle's whole lifetime.
+///
+/// Example: measuring work spread over a couple of threads.
+///
+/// ```rust
+/// # use alloc_counter::{AllocCounterSystem, count_alloc_with, SharedCounters};
+/// # #[global_allocator]
+/// # static A: AllocCounterSystem = AllocCounterSystem;
+/// let handle = SharedCounters::new();
+///
+/// let (info, _) = count_alloc_with(&handle, || {
+///     Box::new(0);
+/// });
+/// assert_eq!(info.allocations, 1);
+///
+/// // A worker thread only contributes to `handle` if it opts in with its own call.
+/// let worker_handle = handle.clone();
+/// std::thread::spawn(move || {
+///     count_alloc_with(&worker_handle, || {
+///         Box::new(0);
+///     });
+/// })
+/// .join()
+/// .unwrap();
+/// assert_eq!(handle.snapshot().allocations, 2);
+/// ```
+pub fn count_alloc_with<F, R>(handle: &Arc<SharedCounters>, f: F) -> (AllocInfo, R)
+where
+    F: FnOnce() -> R,
+{
+    let before = handle.snapshot();
+    let r = with_active_handle(handle, f);
+    let after = handle.snapshot();
+
+    let info = AllocInfo {
+        allocations: after.allocations - before.allocations,
+        reallocations: after.reallocations - before.reallocations,
+        deallocations: after.deallocations - before.deallocations,
+        bytes_allocated: after.bytes_allocated - before.bytes_allocated,
+        bytes_current: after.bytes_current,
+        bytes_max: after.bytes_max,
+    };
+
+    (info, r)
+}
+
+// Makes `handle` the target that `alloc`/`realloc`/`dealloc` additionally record into (on
+// top of the thread-local `INFO`, which is always kept up to date) for the duration of `f`,
+// restoring the enclosing handle (or the thread-local fast path) on the way out.
+fn with_active_handle<F, R>(handle: &Arc<SharedCounters>, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    // Pops on drop, so a panicking `f` doesn't leave `handle` active after the caller's
+    // `Arc` may have been dropped.
+    struct PopOnDrop;
+
+    impl Drop for PopOnDrop {
+        fn drop(&mut self) {
+            ACTIVE_HANDLE.with(|stack| {
+                stack.borrow_mut().pop();
+            });
+        }
+    }
+
+    ACTIVE_HANDLE.with(|stack| stack.borrow_mut().push(Arc::as_ptr(handle)));
+    let _pop = PopOnDrop;
+    f()
+}
+
+/// Apply the allocation mode against a function/closure, like [`guard_fn`], but route the
+/// accounting through `handle` in addition to the thread-local counters.
+///
+/// The violation check looks only at *this thread's* contribution (the same thread-local
+/// counters `guard_fn` checks), not `handle`'s aggregate total: since `handle` is meant to be
+/// written by several threads concurrently, checking its aggregate would make this panic on
+/// another thread's allocations while `f` itself allocated nothing. Use
+/// [`count_alloc_with`]/[`SharedCounters::snapshot`] to read the aggregate instead.
+pub fn guard_fn_with<F, R>(handle: &Arc<SharedCounters>, mode: AllocMode, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _guard = Guard::new(mode);
+    let (info, x) = with_active_handle(handle, || count_alloc_info(f));
+    if mode != AllocMode::Ignore
+        && (info.allocations, info.reallocations, info.deallocations) != (0, 0, 0)
+    {
+        panic!(
+            "allocations: {}, reallocations: {}, deallocations: {}",
+            info.allocations, info.reallocations, info.deallocations,
+        )
+    }
+    x
+}
+
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 /// Configure how allocations are counted
 pub enum AllocMode {
@@ -53,62 +483,182 @@ where
     A: GlobalAlloc,
 {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        if MODE.with(Cell::get) != AllocMode::Ignore {
-            COUNTERS.with(|x| {
-                let mut c = x.get();
-                c.0 += 1;
-                x.set(c);
+        let in_callback = IN_CALLBACK.with(Cell::get);
+        if !in_callback && current_mode() != AllocMode::Ignore {
+            // Always update the thread-local counters, even when a handle is active: they're
+            // what `guard_fn_with` checks to see *this thread's* contribution, since the
+            // handle's own total is an aggregate other threads can also be writing to.
+            let bytes_current = INFO.with(|x| {
+                let mut i = x.get();
+                i.allocations += 1;
+                i.bytes_allocated += layout.size();
+                i.bytes_current += layout.size();
+                i.bytes_max = i.bytes_max.max(i.bytes_current);
+                x.set(i);
+                i.bytes_current
             });
+            record_peak(bytes_current);
+            if let Some(handle) = active_handle() {
+                (*handle).record_alloc(layout.size());
+            }
         }
 
-        self.0.alloc(layout)
+        let ptr = self.0.alloc(layout);
+        if !in_callback {
+            dispatch_callback(AllocOp::Alloc, layout, ptr);
+        }
+        ptr
     }
 
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        if MODE.with(Cell::get) != AllocMode::Ignore {
-            COUNTERS.with(|x| {
-                let mut c = x.get();
-                c.1 += 1;
-                x.set(c);
+        let in_callback = IN_CALLBACK.with(Cell::get);
+        if !in_callback && current_mode() != AllocMode::Ignore {
+            // Always update the thread-local counters; see the comment in `alloc`.
+            let bytes_current = INFO.with(|x| {
+                let mut i = x.get();
+                i.reallocations += 1;
+                i.bytes_allocated += new_size;
+                // Saturating: a counted realloc of memory that was allocated while
+                // uncounted (e.g. under `allow_alloc`, or from inside a callback)
+                // would otherwise underflow `bytes_current`.
+                i.bytes_current = if new_size >= layout.size() {
+                    i.bytes_current + (new_size - layout.size())
+                } else {
+                    i.bytes_current.saturating_sub(layout.size() - new_size)
+                };
+                i.bytes_max = i.bytes_max.max(i.bytes_current);
+                x.set(i);
+                i.bytes_current
             });
+            record_peak(bytes_current);
+            if let Some(handle) = active_handle() {
+                (*handle).record_realloc(layout.size(), new_size);
+            }
         }
 
-        self.0.realloc(ptr, layout, new_size)
+        let new_ptr = self.0.realloc(ptr, layout, new_size);
+        if !in_callback {
+            dispatch_callback(AllocOp::Realloc, layout, new_ptr);
+        }
+        new_ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        if MODE.with(Cell::get) != AllocMode::Ignore {
-            COUNTERS.with(|x| {
-                let mut c = x.get();
-                c.2 += 1;
-                x.set(c);
+        let in_callback = IN_CALLBACK.with(Cell::get);
+        if !in_callback && current_mode() != AllocMode::Ignore {
+            // Always update the thread-local counters; see the comment in `alloc`.
+            INFO.with(|x| {
+                let mut i = x.get();
+                i.deallocations += 1;
+                // Saturating for the same reason as in `realloc`: a counted dealloc of
+                // memory allocated while uncounted must not underflow `bytes_current`.
+                i.bytes_current = i.bytes_current.saturating_sub(layout.size());
+                x.set(i);
             });
+            if let Some(handle) = active_handle() {
+                (*handle).record_dealloc(layout.size());
+            }
         }
 
         self.0.dealloc(ptr, layout);
+        if !in_callback {
+            dispatch_callback(AllocOp::Dealloc, layout, ptr);
+        }
     }
 }
 
-struct Guard(AllocMode);
+struct Guard(bool);
 
 impl Drop for Guard {
     fn drop(&mut self) {
-        MODE.with(|x| x.set(self.0))
+        pop_mode(self.0);
     }
 }
 
 impl Guard {
     fn new(mode: AllocMode) -> Self {
-        Guard(MODE.with(|x| {
-            if x.get() != AllocMode::CountAll {
-                x.replace(mode)
-            } else {
-                AllocMode::CountAll
-            }
-        }))
+        Guard(push_mode(mode))
+    }
+}
+
+/// An RAII guard returned by [`deny_scope`], [`allow_scope`], and [`forbid_scope`] which
+/// restores the previous `AllocMode` on this thread when dropped, panicking first if the
+/// guarded mode was `Count`/`CountAll` and an allocation, reallocation, or deallocation
+/// happened while it was in effect.
+///
+/// Unlike `deny_alloc`/`allow_alloc`/`forbid_alloc`, this doesn't require the guarded
+/// region to be closure-shaped, so it also works across FFI boundaries, around early
+/// returns via `?`, or anywhere else the guarded code doesn't map onto a single closure.
+///
+/// ```rust,should_panic
+/// # use alloc_counter::{AllocCounterSystem, deny_scope};
+/// # #[global_allocator]
+/// # static A: AllocCounterSystem = AllocCounterSystem;
+/// fn try_thing() -> Option<()> {
+///     let _guard = deny_scope();
+///     Some(())?;
+///     Box::new(0); // allocates while `_guard` is in effect
+///     Some(())
+///     // `_guard` drops here, however we leave, restoring the previous mode; it panics
+///     // since it saw the allocation above.
+/// }
+/// try_thing();
+/// ```
+#[must_use = "dropping this immediately (e.g. binding to `_`) ends the scope right away; bind it to a name"]
+pub struct ScopeGuard {
+    mode: AllocMode,
+    before: AllocInfo,
+    _guard: Guard,
+}
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        if self.mode == AllocMode::Ignore {
+            return;
+        }
+        let after = INFO.with(Cell::get);
+        let allocations = after.allocations - self.before.allocations;
+        let reallocations = after.reallocations - self.before.reallocations;
+        let deallocations = after.deallocations - self.before.deallocations;
+        if (allocations, reallocations, deallocations) != (0, 0, 0) {
+            panic!(
+                "allocations: {}, reallocations: {}, deallocations: {}",
+                allocations, reallocations, deallocations,
+            )
+        }
     }
 }
 
+fn new_scope_guard(mode: AllocMode) -> ScopeGuard {
+    let _guard = Guard::new(mode);
+    let before = INFO.with(Cell::get);
+    ScopeGuard {
+        mode,
+        before,
+        _guard,
+    }
+}
+
+/// Enter deny mode for the current thread until the returned guard is dropped, panicking on
+/// drop if an allocation happened while it was in effect. RAII counterpart to [`deny_alloc`]
+/// for regions that aren't closure-shaped.
+pub fn deny_scope() -> ScopeGuard {
+    new_scope_guard(AllocMode::Count)
+}
+
+/// Enter allow mode for the current thread until the returned guard is dropped. Never
+/// panics. RAII counterpart to [`allow_alloc`] for regions that aren't closure-shaped.
+pub fn allow_scope() -> ScopeGuard {
+    new_scope_guard(AllocMode::Ignore)
+}
+
+/// Enter forbid mode for the current thread until the returned guard is dropped, panicking on
+/// drop if an allocation happened while it was in effect. RAII counterpart to
+/// [`forbid_alloc`] for regions that aren't closure-shaped.
+pub fn forbid_scope() -> ScopeGuard {
+    new_scope_guard(AllocMode::CountAll)
+}
+
 /// Count the allocations, reallocations, and deallocations that happen during execution of a
 /// closure.
 ///
@@ -136,11 +686,59 @@ pub fn count_alloc<F, R>(f: F) -> (Counters, R)
 where
     F: FnOnce() -> R,
 {
-    let (a1, r1, d1) = COUNTERS.with(Cell::get);
+    let (info, r) = count_alloc_info(f);
+    ((info.allocations, info.reallocations, info.deallocations), r)
+}
+
+/// Count the allocations, reallocations, and deallocations that happen during execution of
+/// a closure, along with the bytes requested and the peak number of bytes live at once.
+///
+/// `bytes_max` is the peak `bytes_current` observed while `f` was running, not an unbounded
+/// thread-lifetime high-water mark: it starts from whatever was already live when `f` began,
+/// and only rises if `f` itself pushes `bytes_current` higher. This lets callers assert a
+/// function never exceeds a memory budget without tripping over unrelated peaks reached
+/// before or after the call.
+///
+/// `allocations`/`reallocations`/`deallocations`/`bytes_allocated` are deltas covering just
+/// `f`'s execution, and `bytes_max` is scoped to it as described above, but `bytes_current`
+/// is *not* scoped the same way: it's this thread's absolute live-byte count at the moment
+/// `f` returns, including whatever was already live before `f` ran.
+///
+/// Example:
+///
+/// ```rust
+/// # use alloc_counter::{AllocCounterSystem, count_alloc_info};
+/// # #[global_allocator]
+/// # static A: AllocCounterSystem = AllocCounterSystem;
+/// let (info, result) = count_alloc_info(|| {
+///     let mut v = Vec::with_capacity(4);
+///     v.push(0u8);
+///     v.pop().unwrap()
+/// });
+/// assert_eq!(result, 0);
+/// assert_eq!(info.allocations, 1);
+/// assert_eq!(info.bytes_allocated, 4);
+/// ```
+pub fn count_alloc_info<F, R>(f: F) -> (AllocInfo, R)
+where
+    F: FnOnce() -> R,
+{
+    let before = INFO.with(Cell::get);
+    let peak = push_peak(before.bytes_current);
     let r = f();
-    let (a2, r2, d2) = COUNTERS.with(Cell::get);
+    let after = INFO.with(Cell::get);
+    let bytes_max = pop_peak(peak, after.bytes_current.max(before.bytes_current));
 
-    ((a2 - a1, r2 - r1, d2 - d1), r)
+    let info = AllocInfo {
+        allocations: after.allocations - before.allocations,
+        reallocations: after.reallocations - before.reallocations,
+        deallocations: after.deallocations - before.deallocations,
+        bytes_allocated: after.bytes_allocated - before.bytes_allocated,
+        bytes_current: after.bytes_current,
+        bytes_max,
+    };
+
+    (info, r)
 }
 
 /// Count the allocations, reallocations, and deallocations that happen duringexecution of a
@@ -215,6 +813,97 @@ where
     x
 }
 
+/// Apply the allocation mode against a function/closure, aborting the process if any
+/// allocations, reallocations, or deallocations occur, instead of panicking.
+///
+/// Unwinding (and the panic machinery that drives it) can itself allocate, which would
+/// corrupt the counters and potentially re-enter this same `AllocCounter`. Aborting
+/// sidesteps that by never unwinding through the violation, and the diagnostic is written
+/// using only a fixed-size stack buffer so reporting the violation cannot allocate either.
+/// Prefer this over `guard_fn` around code that must never re-enter the allocator while
+/// failing. This function itself requires `std` (for `std::io::stderr` and
+/// `std::process::abort`), so it, like the `deny_alloc_abort`/`forbid_alloc_abort` wrappers
+/// built on it, is not available in `#[no_std]` builds.
+///
+/// `#[no_std]`/embedded targets, where unwinding is often unavailable anyway and a
+/// deterministic abort would be most valuable, are exactly the targets this can't reach:
+/// there's no stable, portable `core`-only abort primitive to call instead of
+/// `std::process::abort` (`core::intrinsics::abort` isn't stable, and a double panic's
+/// behavior in `no_std` depends entirely on the crate's own `#[panic_handler]`). This is a
+/// deliberate, documented scope reduction rather than an oversight; reaching `no_std` would
+/// need a caller-supplied abort hook, which isn't how the rest of this crate's API is shaped.
+#[cfg(feature = "std")]
+pub fn guard_fn_abort<F, R>(mode: AllocMode, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let _guard = Guard::new(mode);
+    let ((allocations, reallocations, deallocations), x) = count_alloc(f);
+    if mode != AllocMode::Ignore && (allocations, reallocations, deallocations) != (0, 0, 0) {
+        report_violation_and_abort(allocations, reallocations, deallocations);
+    }
+    x
+}
+
+#[cfg(feature = "std")]
+fn report_violation_and_abort(allocations: usize, reallocations: usize, deallocations: usize) -> ! {
+    use std::io::Write;
+
+    // Render the diagnostic into a fixed-size stack buffer rather than `format!`/`String`,
+    // since we must not allocate while reporting a forbidden allocation.
+    struct StackBuf {
+        bytes: [u8; 128],
+        len: usize,
+    }
+
+    impl core::fmt::Write for StackBuf {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            let bytes = s.as_bytes();
+            let remaining = self.bytes.len() - self.len;
+            let n = remaining.min(bytes.len());
+            self.bytes[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            Ok(())
+        }
+    }
+
+    let mut buf = StackBuf {
+        bytes: [0; 128],
+        len: 0,
+    };
+    let _ = core::fmt::Write::write_fmt(
+        &mut buf,
+        format_args!(
+            "alloc-counter: forbidden allocation (allocations: {}, reallocations: {}, deallocations: {}), aborting\n",
+            allocations, reallocations, deallocations,
+        ),
+    );
+    let _ = std::io::stderr().write_all(&buf.bytes[..buf.len]);
+    std::process::abort()
+}
+
+/// Abort on any allocations during the provided closure. If code within the closure calls
+/// `allow_alloc`, allocations are allowed within that scope. Aborts the process instead of
+/// panicking, via `guard_fn_abort`, to avoid re-entering the allocator while unwinding.
+#[cfg(feature = "std")]
+pub fn deny_alloc_abort<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    guard_fn_abort(AllocMode::Count, f)
+}
+
+/// Abort on any allocations during the provided closure, even if the closure contains code
+/// in an `allow_alloc` guard. Aborts the process instead of panicking, via
+/// `guard_fn_abort`, to avoid re-entering the allocator while unwinding.
+#[cfg(feature = "std")]
+pub fn forbid_alloc_abort<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    guard_fn_abort(AllocMode::CountAll, f)
+}
+
 /// Apply the allocation mode against a future. Panicking if any allocations,
 /// reallocations or deallocations occur. (Use `guard_fn` for functions)
 pub async fn guard_future<F>(mode: AllocMode, f: F) -> F::Output